@@ -1,6 +1,9 @@
-use std::ops::{Add, Sub, Mul, Div, AddAssign};
+use std::ops::{Add, Sub, Mul, Div, AddAssign, Neg};
 use std::marker::PhantomData;
 
+#[cfg(feature = "serde")]
+use serde::{Deserialize, Serialize};
+
 // Control = P * Measure
 // P = Control / Measure
 
@@ -22,6 +25,38 @@ pub trait PidController<Measure, Control, Time> {
     fn output(&self) -> Control;
 }
 
+/// The individual contributions of the proportional, integral, and
+/// derivative terms to a controller's most recent output.
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Components<T> {
+    pub p: T,
+    pub i: T,
+    pub d: T,
+}
+
+/// A plain-data snapshot of a controller's gains and limits, independent of
+/// the `Measure`/`Time`/`Integral`/`Derivative` unit types that [`Controller`]
+/// is generic over.
+///
+/// This makes it possible to load a controller's tuning from a config file
+/// or send it over the wire: enable the `serde` feature to (de)serialize a
+/// `Parameters`, then build a [`Controller`] from it with
+/// [`Controller::from_parameters`].
+#[cfg_attr(feature = "serde", derive(Serialize, Deserialize))]
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct Parameters<Control, P, I, D> {
+    pub k_p: P,
+    pub k_i: I,
+    pub k_d: D,
+    /// `(min, max)` output bounds. A pair, rather than two independent
+    /// `Option`s, so a config can't set one bound without the other — the
+    /// shape [`Controller::with_output_limits`] requires.
+    pub output_limits: Option<(Control, Control)>,
+    pub p_limit: Option<Control>,
+    pub i_limit: Option<Control>,
+    pub d_limit: Option<Control>,
+}
+
 pub struct Controller<
     Measure,
     Control,
@@ -37,7 +72,16 @@ pub struct Controller<
     k_d: D,
     output: Control,
     previous_error: Measure,
+    previous_measurement: Option<Measure>,
     accumulated_error: Integral,
+    output_min: Option<Control>,
+    output_max: Option<Control>,
+    integral_min: Option<Integral>,
+    integral_max: Option<Integral>,
+    p_limit: Option<Control>,
+    i_limit: Option<Control>,
+    d_limit: Option<Control>,
+    last_components: Components<Control>,
     phantom_time: PhantomData<Time>,
     phantom_derivative: PhantomData<Derivative>,
 }
@@ -47,6 +91,8 @@ impl <Measure, Control, Time, P, I, D, Integral, Derivative>
     Controller<Measure, Control, Time>
     where Measure: Mul<Time, Output=Integral> + Div<Time, Output=Derivative>,
           Control: Add<Control, Output=Control>
+    + Sub<Control, Output=Control>
+    + Copy
     + Div<Measure, Output=P>
     + Div<Integral, Output=I>
     + Div<Derivative, Output=D>,
@@ -55,17 +101,109 @@ impl <Measure, Control, Time, P, I, D, Integral, Derivative>
                initial_output: Control,
                initial_error: Measure,
                initial_accumulated_error: Integral) -> Controller<Measure, Control, Time> {
+        // No update() has run yet, so attribute the initial output entirely
+        // to `p` and zero out `i`/`d` to preserve the
+        // `components().p + i + d == output()` invariant before the first
+        // update.
+        let zero = zero_like(initial_output);
         Controller {
             k_p,
             k_i,
             k_d,
             output: initial_output,
             previous_error: initial_error,
+            previous_measurement: None,
             accumulated_error: initial_accumulated_error,
+            output_min: None,
+            output_max: None,
+            integral_min: None,
+            integral_max: None,
+            p_limit: None,
+            i_limit: None,
+            d_limit: None,
+            last_components: Components {
+                p: initial_output,
+                i: zero,
+                d: zero,
+            },
             phantom_time: PhantomData::<Time>,
             phantom_derivative: PhantomData::<Derivative>,
         }
     }
+
+    /// Clamp the final output into `[min, max]`.
+    ///
+    /// Unset by default, in which case the output is unbounded.
+    pub fn with_output_limits(mut self, min: Control, max: Control) -> Self {
+        self.output_min = Some(min);
+        self.output_max = Some(max);
+        self
+    }
+
+    /// Clamp the accumulated (integral) error into `[min, max]`.
+    ///
+    /// Combined with [`with_output_limits`](Self::with_output_limits), this
+    /// prevents integral windup: once the output saturates, error no longer
+    /// accumulates into the integral term.
+    pub fn with_integral_limits(mut self, min: Integral, max: Integral) -> Self {
+        self.integral_min = Some(min);
+        self.integral_max = Some(max);
+        self
+    }
+
+    /// Clamp the proportional term's contribution to the output into
+    /// `[-limit, +limit]`.
+    pub fn with_p_limit(mut self, limit: Control) -> Self {
+        self.p_limit = Some(limit);
+        self
+    }
+
+    /// Clamp the integral term's contribution to the output into
+    /// `[-limit, +limit]`.
+    pub fn with_i_limit(mut self, limit: Control) -> Self {
+        self.i_limit = Some(limit);
+        self
+    }
+
+    /// Clamp the derivative term's contribution to the output into
+    /// `[-limit, +limit]`.
+    pub fn with_d_limit(mut self, limit: Control) -> Self {
+        self.d_limit = Some(limit);
+        self
+    }
+
+    /// Build a controller from a [`Parameters`] snapshot plus the runtime
+    /// state [`new`](Self::new) otherwise requires.
+    pub fn from_parameters(
+        parameters: Parameters<Control, P, I, D>,
+        initial_output: Control,
+        initial_error: Measure,
+        initial_accumulated_error: Integral,
+    ) -> Controller<Measure, Control, Time> {
+        let mut controller = Controller::new(
+            parameters.k_p,
+            parameters.k_i,
+            parameters.k_d,
+            initial_output,
+            initial_error,
+            initial_accumulated_error,
+        );
+
+        if let Some((min, max)) = parameters.output_limits {
+            controller = controller.with_output_limits(min, max);
+        }
+        if let Some(limit) = parameters.p_limit {
+            controller = controller.with_p_limit(limit);
+        }
+        if let Some(limit) = parameters.i_limit {
+            controller = controller.with_i_limit(limit);
+        }
+        if let Some(limit) = parameters.d_limit {
+            controller = controller.with_d_limit(limit);
+        }
+
+        controller
+    }
 }
 
 
@@ -79,20 +217,22 @@ where
         + Div<Time, Output = Derivative>
         + Copy,
     Control: Add<Control, Output = Control>
+        + Sub<Control, Output = Control>
         + Copy
+        + PartialOrd
+        + Neg<Output = Control>
         + Div<Measure, Output = P>
         + Div<Integral, Output = I>
         + Div<Derivative, Output = D>,
     P: Mul<Measure, Output = Control> + Copy,
     I: Mul<Integral, Output = Control> + Copy,
     D: Mul<<Measure as Div<Time>>::Output, Output = Control> + Copy,
-    Integral: AddAssign<Integral> + Copy,
+    Integral: AddAssign<Integral> + Copy + PartialOrd,
+    Derivative: Neg<Output = Derivative>,
 {
     fn update(&mut self, error: Measure, delta: Time) {
-        self.accumulated_error += error * delta;
         let error_delta = (error - self.previous_error) / delta;
-        self.output = self.k_p * error + self.k_i * self.accumulated_error + self.k_d * error_delta;
-        self.previous_error = error;
+        self.apply(error, delta, error_delta);
     }
 
     fn output(&self) -> Control {
@@ -100,6 +240,205 @@ where
     }
 }
 
+impl<Measure, Control, Time, P, I, D, Integral, Derivative> Controller<Measure, Control, Time>
+where
+    Time: Div<Measure> + Copy,
+    Measure: AddAssign<Measure>
+        + Sub<Measure, Output = Measure>
+        + Mul<Time, Output = Integral>
+        + Div<Time, Output = Derivative>
+        + Copy,
+    Control: Add<Control, Output = Control>
+        + Sub<Control, Output = Control>
+        + Copy
+        + PartialOrd
+        + Neg<Output = Control>
+        + Div<Measure, Output = P>
+        + Div<Integral, Output = I>
+        + Div<Derivative, Output = D>,
+    P: Mul<Measure, Output = Control> + Copy,
+    I: Mul<Integral, Output = Control> + Copy,
+    D: Mul<<Measure as Div<Time>>::Output, Output = Control> + Copy,
+    Integral: AddAssign<Integral> + Copy + PartialOrd,
+    Derivative: Neg<Output = Derivative>,
+{
+    /// Update using a separately tracked setpoint and measurement, computing
+    /// the derivative term from the change in `measurement` rather than the
+    /// change in `error`.
+    ///
+    /// This avoids the derivative kick that [`update`](PidController::update)
+    /// produces when the setpoint jumps: a step change in `setpoint` alone
+    /// no longer spikes the derivative term, since it only reacts to changes
+    /// in `measurement`. There's no previous measurement to diff against on
+    /// the first call, so it falls back to the error-based derivative used
+    /// by `update`.
+    pub fn update_with_setpoint(&mut self, setpoint: Measure, measurement: Measure, elapsed: Time) {
+        let error = setpoint - measurement;
+        let error_delta = match self.previous_measurement {
+            Some(previous_measurement) => -((measurement - previous_measurement) / elapsed),
+            None => (error - self.previous_error) / elapsed,
+        };
+        self.apply(error, elapsed, error_delta);
+        self.previous_measurement = Some(measurement);
+    }
+
+    fn apply(&mut self, error: Measure, delta: Time, error_delta: Derivative) {
+        if in_range(self.output, self.output_min, self.output_max) {
+            self.accumulated_error += error * delta;
+        }
+        self.accumulated_error = clamp(self.accumulated_error, self.integral_min, self.integral_max);
+
+        let p = clamp_symmetric(self.k_p * error, self.p_limit);
+        let i = clamp_symmetric(self.k_i * self.accumulated_error, self.i_limit);
+        let d = clamp_symmetric(self.k_d * error_delta, self.d_limit);
+
+        let unclamped_output = p + i + d;
+        let output = clamp(unclamped_output, self.output_min, self.output_max);
+
+        // Keep components() consistent with output(): if the output clamp
+        // changed the sum, attribute the difference to `p` (matching how
+        // `new` seeds last_components) so `p + i + d == output()` always
+        // holds, even while the output is saturated.
+        let p = p + (output - unclamped_output);
+
+        self.output = output;
+        self.previous_error = error;
+        self.last_components = Components { p, i, d };
+    }
+
+    /// The proportional, integral, and derivative contributions to the most
+    /// recent [`output`](PidController::output).
+    pub fn components(&self) -> Components<Control> {
+        self.last_components
+    }
+}
+
+/// Clamp `value` into `[min, max]`, where either bound may be absent.
+fn clamp<V: PartialOrd>(value: V, min: Option<V>, max: Option<V>) -> V {
+    let value = match min {
+        Some(min) if value < min => min,
+        _ => value,
+    };
+    match max {
+        Some(max) if value > max => max,
+        _ => value,
+    }
+}
+
+/// Whether `value` lies strictly inside `[min, max]`, where either bound may
+/// be absent. A missing bound is treated as unbounded on that side.
+fn in_range<V: PartialOrd>(value: V, min: Option<V>, max: Option<V>) -> bool {
+    min.map_or(true, |min| value > min) && max.map_or(true, |max| value < max)
+}
+
+/// Clamp `value` into `[-limit, +limit]`, where an absent limit leaves
+/// `value` unbounded.
+fn clamp_symmetric<V: PartialOrd + Neg<Output = V> + Copy>(value: V, limit: Option<V>) -> V {
+    match limit {
+        Some(limit) => clamp(value, Some(-limit), Some(limit)),
+        None => value,
+    }
+}
+
+/// A "zero" value of the same type as `value`, derived without requiring a
+/// `Default`/`Zero` bound, since not every unit type in this crate has one.
+#[allow(clippy::eq_op)]
+fn zero_like<V: Copy + Sub<V, Output = V>>(value: V) -> V {
+    value - value
+}
+
+/// A PID controller using the velocity (incremental) form.
+///
+/// Rather than maintaining an accumulated error, this recomputes the output
+/// incrementally from the last two errors and the last output, using the
+/// backward-difference recurrence
+/// `y0 = y1 + k_p*(x0 - x1) + k_i*x0*delta + k_d*(x0 - 2*x1 + x2)/delta`.
+/// It produces the same steady-state behavior as [`Controller`] but never
+/// stores an accumulated error, so clamping `y0` is all that's needed to
+/// saturate the output without windup. `VelocityController` itself has no
+/// output limits: `update` adds each increment to the output unclamped.
+pub struct VelocityController<
+    Measure,
+    Control,
+    Time,
+    Integral = <Measure as Mul<Time>>::Output,
+    Derivative = <Measure as Div<Time>>::Output,
+    P = <Control as Div<Measure>>::Output,
+    I = <Control as Div<Integral>>::Output,
+    D = <Control as Div<Derivative>>::Output,
+> {
+    k_p: P,
+    k_i: I,
+    k_d: D,
+    x1: Measure,
+    x2: Measure,
+    y1: Control,
+    phantom_time: PhantomData<Time>,
+    phantom_integral: PhantomData<Integral>,
+    phantom_derivative: PhantomData<Derivative>,
+}
+
+impl<Measure, Control, Time, P, I, D, Integral, Derivative>
+    VelocityController<Measure, Control, Time>
+    where Measure: Mul<Time, Output=Integral> + Div<Time, Output=Derivative> + Copy,
+          Control: Add<Control, Output=Control>
+    + Div<Measure, Output=P>
+    + Div<Integral, Output=I>
+    + Div<Derivative, Output=D>,
+{
+    pub fn new(k_p: P, k_i: I, k_d: D,
+               initial_output: Control,
+               initial_error: Measure) -> VelocityController<Measure, Control, Time> {
+        VelocityController {
+            k_p,
+            k_i,
+            k_d,
+            x1: initial_error,
+            x2: initial_error,
+            y1: initial_output,
+            phantom_time: PhantomData::<Time>,
+            phantom_integral: PhantomData::<Integral>,
+            phantom_derivative: PhantomData::<Derivative>,
+        }
+    }
+}
+
+impl<Measure, Control, Time, P, I, D, Integral, Derivative> PidController<Measure, Control, Time>
+    for VelocityController<Measure, Control, Time>
+where
+    Time: Div<Measure> + Copy,
+    Measure: Sub<Measure, Output = Measure>
+        + Mul<Time, Output = Integral>
+        + Div<Time, Output = Derivative>
+        + Copy,
+    Control: Add<Control, Output = Control>
+        + Copy
+        + Div<Measure, Output = P>
+        + Div<Integral, Output = I>
+        + Div<Derivative, Output = D>,
+    P: Mul<Measure, Output = Control> + Copy,
+    I: Mul<Integral, Output = Control> + Copy,
+    D: Mul<Derivative, Output = Control> + Copy,
+{
+    fn update(&mut self, error: Measure, delta: Time) {
+        let dx1 = error - self.x1;
+        let dx2 = self.x1 - self.x2;
+        let second_difference = dx1 - dx2;
+
+        let p = self.k_p * dx1;
+        let i = self.k_i * (error * delta);
+        let d = self.k_d * (second_difference / delta);
+
+        self.y1 = self.y1 + p + i + d;
+        self.x2 = self.x1;
+        self.x1 = error;
+    }
+
+    fn output(&self) -> Control {
+        self.y1
+    }
+}
+
 #[cfg(test)]
 mod tests {
     extern crate dimensioned;
@@ -147,4 +486,200 @@ mod tests {
 
         controller.update(1.0 * si::M, 1.0 * si::S);
     }
+
+    #[test]
+    fn output_clamps_to_limits() {
+        let mut controller = Controller::new(1.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+            .with_output_limits(-1.0, 1.0);
+
+        controller.update(10.0, 1.0);
+
+        assert_eq!(controller.output(), 1.0);
+    }
+
+    #[test]
+    fn anti_windup_stops_integrating_while_output_is_saturated() {
+        let mut controller = Controller::new(0.0, 1.0, 0.0, 0.0, 0.0, 0.0)
+            .with_output_limits(-1.0, 1.0);
+
+        controller.update(10.0, 1.0);
+        assert_eq!(controller.output(), 1.0);
+        let accumulated_while_saturated = controller.accumulated_error;
+
+        // The output was already pinned to its max, so further error should
+        // not be folded into the integral term: that's the windup this
+        // request exists to prevent.
+        controller.update(10.0, 1.0);
+
+        assert_eq!(controller.accumulated_error, accumulated_while_saturated);
+        assert_eq!(controller.output(), 1.0);
+    }
+
+    #[test]
+    fn with_integral_limits_clamps_accumulated_error() {
+        let mut controller = Controller::new(0.0, 1.0, 0.0, 0.0, 0.0, 0.0)
+            .with_integral_limits(-5.0, 5.0);
+
+        controller.update(10.0, 1.0);
+
+        assert_eq!(controller.accumulated_error, 5.0);
+        assert_eq!(controller.output(), 5.0);
+    }
+
+    #[test]
+    fn with_p_limit_clamps_proportional_term() {
+        let mut controller = Controller::new(2.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+            .with_p_limit(1.0);
+
+        controller.update(10.0, 1.0);
+
+        assert_eq!(controller.output(), 1.0);
+    }
+
+    #[test]
+    fn with_i_limit_clamps_integral_term() {
+        let mut controller = Controller::new(0.0, 2.0, 0.0, 0.0, 0.0, 0.0)
+            .with_i_limit(1.0);
+
+        controller.update(10.0, 1.0);
+
+        assert_eq!(controller.output(), 1.0);
+    }
+
+    #[test]
+    fn with_d_limit_clamps_derivative_term() {
+        let mut controller = Controller::new(0.0, 0.0, 2.0, 0.0, 0.0, 0.0)
+            .with_d_limit(1.0);
+
+        controller.update(10.0, 1.0);
+
+        assert_eq!(controller.output(), 1.0);
+    }
+
+    #[test]
+    fn update_with_setpoint_ignores_setpoint_jump() {
+        let mut controller = Controller::new(0.0, 0.0, 1.0, 0.0, 0.0, 0.0);
+
+        controller.update_with_setpoint(0.0, 0.0, 1.0);
+        let output_a = controller.output();
+
+        // A setpoint jump with the measurement unchanged should not spike
+        // the derivative term, since the derivative only reacts to changes
+        // in the measurement.
+        controller.update_with_setpoint(5.0, 0.0, 1.0);
+        let output_b = controller.output();
+
+        assert_eq!(output_a, output_b);
+    }
+
+    #[test]
+    fn from_parameters_applies_gains_and_limits() {
+        let parameters = Parameters {
+            k_p: 1.0,
+            k_i: 0.5,
+            k_d: 0.0,
+            output_limits: Some((-1.0, 1.0)),
+            p_limit: None,
+            i_limit: None,
+            d_limit: None,
+        };
+        let mut controller = Controller::from_parameters(parameters, 0.0, 0.0, 0.0);
+
+        controller.update(10.0, 1.0);
+
+        assert_eq!(controller.output(), 1.0);
+    }
+
+    #[cfg(feature = "serde")]
+    #[test]
+    fn parameters_round_trip_through_serde() {
+        let parameters = Parameters {
+            k_p: 1.0,
+            k_i: 0.5,
+            k_d: 0.25,
+            output_limits: Some((-1.0, 1.0)),
+            p_limit: None,
+            i_limit: None,
+            d_limit: None,
+        };
+
+        let json = serde_json::to_string(&parameters).unwrap();
+        let round_tripped: Parameters<f64, f64, f64, f64> = serde_json::from_str(&json).unwrap();
+
+        assert_eq!(parameters, round_tripped);
+    }
+
+    #[test]
+    fn components_reports_last_term_contributions() {
+        let mut controller = Controller::new(2.0, 0.5, 0.0, 0.0, 0.0, 0.0);
+
+        controller.update(1.0, 1.0);
+        let components = controller.components();
+
+        assert_eq!(components.p, 2.0);
+        assert_eq!(components.i, 0.5);
+        assert_eq!(components.d, 0.0);
+        assert_eq!(components.p + components.i + components.d, controller.output());
+    }
+
+    #[test]
+    fn components_match_output_before_first_update() {
+        let controller: Controller<f64, f64, f64> = Controller::new(1.0, 1.0, 1.0, 3.0, 0.0, 0.0);
+
+        let components = controller.components();
+
+        assert_eq!(components.p + components.i + components.d, controller.output());
+    }
+
+    #[test]
+    fn components_match_output_when_output_is_clamped() {
+        let mut controller = Controller::new(1.0, 0.0, 0.0, 0.0, 0.0, 0.0)
+            .with_output_limits(-1.0, 1.0);
+
+        controller.update(10.0, 1.0);
+        let components = controller.components();
+
+        assert_eq!(controller.output(), 1.0);
+        assert_eq!(components.p + components.i + components.d, controller.output());
+    }
+
+    #[test]
+    fn velocity_no_integral_gain() {
+        let mut controller = VelocityController::new(1.0, 0.0, 0.0, 0., 0.);
+
+        controller.update(1.0, 1.0);
+        let output_a = controller.output();
+
+        controller.update(1.0, 1.0);
+        let output_b = controller.output();
+
+        assert_eq!(output_a, output_b);
+    }
+
+    #[test]
+    fn velocity_with_integral_gain() {
+        let mut controller = VelocityController::new(1.0, 0.5, 0.0, 0.0, 0.0);
+
+        controller.update(1.0, 1.0);
+        let output_a = controller.output();
+
+        controller.update(1.0, 1.0);
+        let output_b = controller.output();
+
+        assert!(output_a < output_b);
+    }
+
+    #[test]
+    fn velocity_with_units() {
+        let mut controller: VelocityController<si::Meter<f64>, si::Joule<f64>, si::Second<f64>> =
+            VelocityController::new(
+                1.0 * si::J / si::M,
+                0.2 * si::J / (si::M * si::S),
+                0.5 * si::J / (si::M / si::S),
+                0.0 * si::J,
+                0.0 * si::M,
+            );
+
+        controller.update(1.0 * si::M, 1.0 * si::S);
+    }
 }